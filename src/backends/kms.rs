@@ -1,11 +1,15 @@
 //! Backend for DRM/KMS for raw rendering directly to the screen.
 //!
-//! This strategy uses dumb buffers for rendering.
+//! This strategy uses dumb buffers for rendering by default. With the `kms-gbm`
+//! feature enabled, it instead prefers allocating through a `gbm::Device` when the
+//! card supports it, which lets the driver pick its preferred tiling/modifiers for
+//! scanout instead of forcing linear, CPU-mapped memory.
 
 use drm::control::{
-    connector, crtc,
+    atomic::AtomicModeReq, connector, crtc,
     dumbbuffer::{DumbBuffer, DumbMapping},
-    framebuffer, Device as CtrlDevice, Mode, PageFlipFlags, ResourceHandles,
+    framebuffer, plane, property, AtomicCommitFlags, ClientCapability, Device as CtrlDevice, Event,
+    Mode, ModeTypeFlags, PageFlipFlags, PlaneType, ResourceHandles,
 };
 use drm::{buffer::DrmFourcc, Device};
 
@@ -13,7 +17,7 @@ use raw_window_handle::{HasDisplayHandle, HasWindowHandle, RawDisplayHandle};
 use tracing::{error, warn};
 
 use std::marker::PhantomData;
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::os::unix::io::{AsFd, BorrowedFd};
 use std::sync::Arc;
 
@@ -28,6 +32,18 @@ pub(crate) struct KmsDisplayImpl<D: ?Sized> {
     /// The underlying raw device file descriptor.
     fd: BorrowedFd<'static>,
 
+    /// Whether the device accepted `ClientCapability::Atomic`.
+    ///
+    /// When `true`, surfaces created on top of this display use atomic commits for
+    /// modesetting and presentation instead of the legacy `set_crtc`/`page_flip` calls.
+    atomic: bool,
+
+    /// The GBM allocator for this device, if the `kms-gbm` feature is enabled and the
+    /// card accepted opening one. `SharedBuffer::new` prefers this over dumb buffers
+    /// when it's present.
+    #[cfg(feature = "kms-gbm")]
+    gbm: Option<gbm::Device<BorrowedFd<'static>>>,
+
     /// Holds a reference to the display.
     _display: D,
 }
@@ -54,9 +70,29 @@ impl<D: HasDisplayHandle + ?Sized> ContextInterface<D> for Arc<KmsDisplayImpl<D>
         // SAFETY: Invariants guaranteed by the user.
         let fd = unsafe { BorrowedFd::borrow_raw(card_fd) };
 
-        Ok(Arc::new(KmsDisplayImpl {
+        let display = KmsDisplayImpl {
             fd,
+            // Not every driver supports atomic modesetting; fall back to the legacy
+            // KMS path transparently when the kernel rejects the capability.
+            atomic: false,
+            #[cfg(feature = "kms-gbm")]
+            gbm: None,
             _display: display,
+        };
+        let atomic = display
+            .set_client_capability(ClientCapability::Atomic, true)
+            .is_ok();
+
+        // Probe for GBM support; fall back to dumb buffers (the default) when the
+        // card can't be opened as a GBM device.
+        #[cfg(feature = "kms-gbm")]
+        let gbm = gbm::Device::new(fd).ok();
+
+        Ok(Arc::new(KmsDisplayImpl {
+            atomic,
+            #[cfg(feature = "kms-gbm")]
+            gbm,
+            ..display
         }))
     }
 }
@@ -77,10 +113,47 @@ pub(crate) struct KmsImpl<D: ?Sized, W: ?Sized> {
 
     mode: Mode,
 
+    /// Cached atomic property handles, present only when the display supports
+    /// atomic modesetting (see `KmsDisplayImpl::atomic`).
+    atomic: Option<AtomicState>,
+
+    /// Whether a page flip we issued is still in flight, i.e. we haven't yet seen its
+    /// `PageFlip` event come back from the kernel.
+    flip_pending: bool,
+
+    /// How many buffers to allocate on the next `resize`. Defaults to double-buffering;
+    /// bump it (e.g. to triple-buffer) to avoid stalling `buffer_mut` while a flip is
+    /// still pending.
+    buffer_count: NonZeroUsize,
+
     /// Window handle that we are keeping around.
     window: W,
 }
 
+/// Object property handles cached once at surface creation so that every modeset and
+/// presentation can build an `AtomicModeReq` without re-querying the kernel for them.
+#[derive(Debug, Clone, Copy)]
+struct AtomicState {
+    /// The primary plane we scan out from.
+    plane: plane::Handle,
+    connector_crtc_id: property::Handle,
+    crtc_mode_id: property::Handle,
+    crtc_active: property::Handle,
+    plane_fb_id: property::Handle,
+    plane_crtc_id: property::Handle,
+    plane_src_x: property::Handle,
+    plane_src_y: property::Handle,
+    plane_src_w: property::Handle,
+    plane_src_h: property::Handle,
+    plane_crtc_x: property::Handle,
+    plane_crtc_y: property::Handle,
+    plane_crtc_w: property::Handle,
+    plane_crtc_h: property::Handle,
+    /// Not every driver exposes `FB_DAMAGE_CLIPS`; damage hints are simply skipped
+    /// when it's missing.
+    plane_fb_damage_clips: Option<property::Handle>,
+}
+
 impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> for KmsImpl<D, W> {
     type Context = Arc<KmsDisplayImpl<D>>;
     type Buffer<'a>
@@ -99,14 +172,27 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> fo
 
         let conn = find_connector(display, &res)?;
         let crtc = find_crtc(display, &res, &conn)?;
-        // The first mode is always the one with the highest resolution (as stated by drm-kms(7))
-        let mode = *conn.modes().first().expect("No modes found on connector");
+        // Default to the connector's preferred mode rather than blindly taking the
+        // first one; callers that want a specific resolution/refresh rate can still
+        // switch through `resize`.
+        let mode = select_preferred_mode(&conn);
+
+        let atomic = display
+            .atomic
+            .then(|| find_atomic_state(display, &res, &conn, &crtc))
+            .flatten();
+        if display.atomic && atomic.is_none() {
+            warn!("Atomic modesetting is supported but a required object property was missing; falling back to the legacy KMS path");
+        }
 
         Ok(KmsImpl {
             buffers: None,
             crtc,
             conn,
             mode,
+            atomic,
+            flip_pending: false,
+            buffer_count: NonZeroUsize::new(2).unwrap(),
 
             window,
             display: Arc::clone(display),
@@ -118,60 +204,275 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> fo
     }
 
     fn resize(&mut self, width: NonZeroU32, height: NonZeroU32) -> Result<(), SoftBufferError> {
-        assert_eq!(self.mode.size().0, u32::from(width) as u16);
-        assert_eq!(self.mode.size().1, u32::from(height) as u16);
+        // A previous frame's flip may still be in flight; tearing down its
+        // framebuffers or issuing a modeset while the kernel hasn't confirmed it yet
+        // would otherwise risk an `EBUSY` from the driver.
+        wait_for_flip(&self.display, &mut self.flip_pending)?;
 
-        let buf1 = SharedBuffer::new(&self.display, width, height)?;
-        let buf2 = SharedBuffer::new(&self.display, width, height)?;
-        let fb = buf1.fb;
+        let target = (u32::from(width) as u16, u32::from(height) as u16);
+        self.mode = *self
+            .conn
+            .modes()
+            .iter()
+            .find(|mode| mode.size() == target)
+            .ok_or_else(|| {
+                SoftBufferError::PlatformError(
+                    Some(format!(
+                        "No mode matching {}x{} found on connector",
+                        target.0, target.1
+                    )),
+                    None,
+                )
+            })?;
 
-        self.buffers = Some(Buffers {
-            buffers: [buf1, buf2],
-            first_is_front: true,
-        });
+        let buffers = (0..self.buffer_count.get())
+            .map(|_| SharedBuffer::new(&self.display, width, height))
+            .collect::<Result<Vec<_>, _>>()?;
+        let fb = buffers[0].fb;
 
-        self.display
-            .set_crtc(
-                self.crtc.handle(),
-                Some(fb),
-                (0, 0),
-                &[self.conn.handle()],
-                Some(self.mode),
-            )
-            .unwrap();
+        // Tear down the previous generation's framebuffers/dumb buffers now that the
+        // new ones were allocated successfully; `DumbBuffer`/`framebuffer::Handle`
+        // don't free their kernel resources on `Drop`, so leaving this out would leak
+        // GEM handles and FB ids on every mode switch.
+        if let Some(old) = self.buffers.replace(Buffers {
+            buffers,
+            next: 0,
+            front: None,
+        }) {
+            for buf in old.buffers {
+                buf.destroy(&self.display);
+            }
+        }
+
+        match &self.atomic {
+            Some(atomic) => self.modeset_atomic(atomic, fb, width, height)?,
+            None => {
+                self.display
+                    .set_crtc(
+                        self.crtc.handle(),
+                        Some(fb),
+                        (0, 0),
+                        &[self.conn.handle()],
+                        Some(self.mode),
+                    )
+                    .swbuf_err("Failed to set CRTC")?;
+            }
+        }
 
         Ok(())
     }
 
     fn fetch(&mut self) -> Result<Vec<u32>, SoftBufferError> {
-        unimplemented!()
+        let buffers = self.buffers.as_mut().ok_or_else(|| {
+            SoftBufferError::PlatformError(
+                Some("`fetch` was called before `resize`".into()),
+                None,
+            )
+        })?;
+
+        // Before the first present, the buffer on screen is the one `resize` modeset
+        // with, i.e. buffer 0.
+        let front = &mut buffers.buffers[buffers.front.unwrap_or(0)];
+
+        let (width, height) = front.size();
+        let (width, height) = (width as usize, height as usize);
+        let pitch = front.pitch() as usize;
+
+        let mapping = front.map(&self.display)?;
+        let bytes = mapping.as_bytes();
+
+        // The dumb buffer's pitch may be larger than `width * 4` (driver-dependent
+        // row alignment); repack row by row so the result is tightly packed.
+        let mut pixels = vec![0u32; width * height];
+        for (row, dst) in pixels.chunks_exact_mut(width).enumerate() {
+            let src = &bytes[row * pitch..row * pitch + width * 4];
+            dst.copy_from_slice(bytemuck::cast_slice(src));
+        }
+
+        Ok(pixels)
     }
 
     fn buffer_mut(&mut self) -> Result<BufferImpl<'_, D, W>, SoftBufferError> {
+        // Clear out the previous flip's event, if it already landed, so we don't
+        // mistake it for the one we're about to issue.
+        self.poll_flip_events()?;
+
         let buffers = self.buffers.as_mut().expect("Need to call resize first...");
 
-        let front = if buffers.first_is_front {
-            &mut buffers.buffers[0]
-        } else {
-            &mut buffers.buffers[1]
-        };
-        buffers.first_is_front = !buffers.first_is_front;
+        let index = buffers.next;
+        buffers.next = (buffers.next + 1) % buffers.buffers.len();
 
-        let mapping = self
-            .display
-            .map_dumb_buffer(&mut front.db)
-            .swbuf_err("Failed to map dumb buffer")?;
+        // Split off the buffer we're handing out so its `backing`/`age` can be
+        // borrowed independently of every other buffer's `age`. Neither the aging
+        // below nor the reset to 0 happens here: both only take effect once this
+        // buffer is actually flipped (see `BufferImpl::flip`), not just because it
+        // was borrowed. Otherwise a caller that drops a `BufferImpl` without
+        // presenting (e.g. an aborted frame) would desynchronize `age()` from
+        // `front_index`, and age every other buffer for a frame that never happened.
+        let (before, rest) = buffers.buffers.split_at_mut(index);
+        let (current, after) = rest.split_at_mut(1);
+        let current = &mut current[0];
+
+        let age = current.age.unwrap_or(0);
+        let fb = current.fb;
+        let mapping = current.backing.map(&self.display)?;
+
+        let other_ages = before
+            .iter_mut()
+            .chain(after.iter_mut())
+            .map(|buf| &mut buf.age)
+            .collect();
+
+        let (width, height) = self.mode.size();
 
         Ok(BufferImpl {
-            fb: front.fb,
+            fb,
             display: &self.display,
             _window: PhantomData,
             crtc: self.crtc.handle(),
+            atomic: self.atomic,
+            flip_pending: &mut self.flip_pending,
+            width: u32::from(width),
+            height: u32::from(height),
+            age,
+            age_slot: &mut current.age,
+            other_ages,
+            index,
+            front_index: &mut buffers.front,
             mapping,
         })
     }
 }
 
+/// KMS-specific configuration that isn't part of the cross-backend `Surface` API.
+///
+/// Implemented for the KMS backend's surface type; import this trait to reach the
+/// methods on any `Surface` backed by it.
+pub trait KmsSurfaceExt {
+    /// List the modes advertised by the connector this surface is attached to.
+    ///
+    /// Each `Mode` carries its resolution and refresh rate, and `mode_type()` reports
+    /// whether it's the `PREFERRED` mode. Pass the `width`/`height` of the mode you want
+    /// to `resize` into to switch to it.
+    fn modes(&self) -> &[Mode];
+
+    /// Set how many buffers to allocate on the next `resize` (the backend defaults to
+    /// 2, i.e. double-buffering). Triple-buffering trades a bit of memory for not
+    /// stalling `buffer_mut` while a previous flip is still in flight.
+    fn set_buffer_count(&mut self, count: NonZeroUsize);
+}
+
+impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> KmsSurfaceExt for KmsImpl<D, W> {
+    fn modes(&self) -> &[Mode] {
+        self.conn.modes()
+    }
+
+    fn set_buffer_count(&mut self, count: NonZeroUsize) {
+        self.buffer_count = count;
+    }
+}
+
+impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> KmsImpl<D, W> {
+    /// Drain any already-completed page-flip events without blocking, clearing
+    /// `flip_pending` once the in-flight flip has landed.
+    fn poll_flip_events(&mut self) -> Result<(), SoftBufferError> {
+        poll_flip_events(&self.display, &mut self.flip_pending)
+    }
+
+    /// Perform a modeset through an atomic commit, setting the CRTC active with the
+    /// given mode and pointing the primary plane at `fb`.
+    fn modeset_atomic(
+        &self,
+        atomic: &AtomicState,
+        fb: framebuffer::Handle,
+        width: NonZeroU32,
+        height: NonZeroU32,
+    ) -> Result<(), SoftBufferError> {
+        let mode_blob = self
+            .display
+            .create_property_blob(&self.mode)
+            .swbuf_err("Failed to create mode property blob")?;
+
+        let mut req = AtomicModeReq::new();
+        req.add_property(
+            self.conn.handle(),
+            atomic.connector_crtc_id,
+            property::Value::CRTC(Some(self.crtc.handle())),
+        );
+        req.add_property(self.crtc.handle(), atomic.crtc_mode_id, mode_blob);
+        req.add_property(
+            self.crtc.handle(),
+            atomic.crtc_active,
+            property::Value::Boolean(true),
+        );
+        req.add_property(
+            atomic.plane,
+            atomic.plane_fb_id,
+            property::Value::Framebuffer(Some(fb)),
+        );
+        req.add_property(
+            atomic.plane,
+            atomic.plane_crtc_id,
+            property::Value::CRTC(Some(self.crtc.handle())),
+        );
+        // Source rectangles are in 16.16 fixed point.
+        req.add_property(
+            atomic.plane,
+            atomic.plane_src_x,
+            property::Value::UnsignedRange(0),
+        );
+        req.add_property(
+            atomic.plane,
+            atomic.plane_src_y,
+            property::Value::UnsignedRange(0),
+        );
+        req.add_property(
+            atomic.plane,
+            atomic.plane_src_w,
+            property::Value::UnsignedRange(u64::from(width.get()) << 16),
+        );
+        req.add_property(
+            atomic.plane,
+            atomic.plane_src_h,
+            property::Value::UnsignedRange(u64::from(height.get()) << 16),
+        );
+        req.add_property(
+            atomic.plane,
+            atomic.plane_crtc_x,
+            property::Value::SignedRange(0),
+        );
+        req.add_property(
+            atomic.plane,
+            atomic.plane_crtc_y,
+            property::Value::SignedRange(0),
+        );
+        req.add_property(
+            atomic.plane,
+            atomic.plane_crtc_w,
+            property::Value::UnsignedRange(u64::from(width.get())),
+        );
+        req.add_property(
+            atomic.plane,
+            atomic.plane_crtc_h,
+            property::Value::UnsignedRange(u64::from(height.get())),
+        );
+
+        self.display
+            .atomic_commit(AtomicCommitFlags::ALLOW_MODESET, req)
+            .swbuf_err("Failed to commit atomic modeset")
+    }
+}
+
+/// Select a connector's preferred mode, falling back to the first mode listed if none
+/// is marked `PREFERRED`.
+fn select_preferred_mode(conn: &connector::Info) -> Mode {
+    *conn
+        .modes()
+        .iter()
+        .find(|mode| mode.mode_type().contains(ModeTypeFlags::PREFERRED))
+        .unwrap_or_else(|| conn.modes().first().expect("No modes found on connector"))
+}
+
 /// Find a display connector on which to render.
 ///
 /// Right now, this selects the first connector that has a display currently attached.
@@ -218,11 +519,194 @@ fn find_crtc<D: HasDisplayHandle + ?Sized, W: HasWindowHandle>(
     Err(SoftBufferError::PlatformError(Some("No compatible CRTC found".into()), None).into())
 }
 
+/// Find a plane compatible with `crtc`, preferring one of type `Primary` and otherwise
+/// taking the first compatible plane.
+fn find_plane<D: ?Sized>(
+    display: &KmsDisplayImpl<D>,
+    res: &ResourceHandles,
+    crtc: &crtc::Info,
+) -> Option<plane::Handle> {
+    let planes = display.plane_handles().ok()?;
+    let compatible: Vec<plane::Handle> = planes
+        .iter()
+        .copied()
+        .filter(|&plane| {
+            display.get_plane(plane).is_ok_and(|plane| {
+                res.filter_crtcs(plane.possible_crtcs())
+                    .contains(&crtc.handle())
+            })
+        })
+        .collect();
+
+    compatible
+        .iter()
+        .copied()
+        .find(|&plane| property_value(display, plane, "type") == Some(PlaneType::Primary as u64))
+        .or_else(|| compatible.first().copied())
+}
+
+/// Cache the object property handles needed to drive this surface through atomic commits.
+///
+/// Returns `None` if any required property is missing on this driver, in which case the
+/// caller should fall back to the legacy KMS path.
+fn find_atomic_state<D: ?Sized>(
+    display: &KmsDisplayImpl<D>,
+    res: &ResourceHandles,
+    conn: &connector::Info,
+    crtc: &crtc::Info,
+) -> Option<AtomicState> {
+    let plane = find_plane(display, res, crtc)?;
+
+    Some(AtomicState {
+        plane,
+        connector_crtc_id: find_property(display, conn.handle(), "CRTC_ID")?,
+        crtc_mode_id: find_property(display, crtc.handle(), "MODE_ID")?,
+        crtc_active: find_property(display, crtc.handle(), "ACTIVE")?,
+        plane_fb_id: find_property(display, plane, "FB_ID")?,
+        plane_crtc_id: find_property(display, plane, "CRTC_ID")?,
+        plane_src_x: find_property(display, plane, "SRC_X")?,
+        plane_src_y: find_property(display, plane, "SRC_Y")?,
+        plane_src_w: find_property(display, plane, "SRC_W")?,
+        plane_src_h: find_property(display, plane, "SRC_H")?,
+        plane_crtc_x: find_property(display, plane, "CRTC_X")?,
+        plane_crtc_y: find_property(display, plane, "CRTC_Y")?,
+        plane_crtc_w: find_property(display, plane, "CRTC_W")?,
+        plane_crtc_h: find_property(display, plane, "CRTC_H")?,
+        plane_fb_damage_clips: find_property(display, plane, "FB_DAMAGE_CLIPS"),
+    })
+}
+
+/// Look up the handle of the property named `name` on `object`.
+fn find_property<D: ?Sized>(
+    display: &KmsDisplayImpl<D>,
+    object: impl drm::control::ResourceHandle,
+    name: &str,
+) -> Option<property::Handle> {
+    let props = display.get_properties(object).ok()?;
+    let (ids, _) = props.as_props_and_values();
+    ids.iter()
+        .copied()
+        .find(|&id| display.get_property(id).is_ok_and(|info| info.name().to_str() == Ok(name)))
+}
+
+/// Look up the raw value of the property named `name` on `object`.
+fn property_value<D: ?Sized>(
+    display: &KmsDisplayImpl<D>,
+    object: impl drm::control::ResourceHandle,
+    name: &str,
+) -> Option<u64> {
+    let props = display.get_properties(object).ok()?;
+    let (ids, vals) = props.as_props_and_values();
+    ids.iter().zip(vals.iter()).find_map(|(&id, &val)| {
+        display
+            .get_property(id)
+            .is_ok_and(|info| info.name().to_str() == Ok(name))
+            .then_some(val)
+    })
+}
+
+/// Drain any already-completed page-flip events without blocking, clearing
+/// `*flip_pending` once the in-flight flip has landed.
+fn poll_flip_events<D: ?Sized>(
+    display: &KmsDisplayImpl<D>,
+    flip_pending: &mut bool,
+) -> Result<(), SoftBufferError> {
+    if !*flip_pending {
+        return Ok(());
+    }
+
+    let readable = rustix::event::poll(
+        &mut [rustix::event::PollFd::new(
+            display,
+            rustix::event::PollFlags::IN,
+        )],
+        Some(0),
+    )
+    .swbuf_err("Failed to poll DRM card fd")?;
+
+    if readable > 0 {
+        drain_flip_events(display, flip_pending)?;
+    }
+
+    Ok(())
+}
+
+/// Block until the in-flight page flip, if any, completes.
+fn wait_for_flip<D: ?Sized>(
+    display: &KmsDisplayImpl<D>,
+    flip_pending: &mut bool,
+) -> Result<(), SoftBufferError> {
+    while *flip_pending {
+        rustix::event::poll(
+            &mut [rustix::event::PollFd::new(
+                display,
+                rustix::event::PollFlags::IN,
+            )],
+            None,
+        )
+        .swbuf_err("Failed to poll DRM card fd")?;
+        drain_flip_events(display, flip_pending)?;
+    }
+
+    Ok(())
+}
+
+/// Read and discard pending DRM events, clearing `*flip_pending` on `PageFlip`.
+fn drain_flip_events<D: ?Sized>(
+    display: &KmsDisplayImpl<D>,
+    flip_pending: &mut bool,
+) -> Result<(), SoftBufferError> {
+    for event in display
+        .receive_events()
+        .swbuf_err("Failed to receive DRM events")?
+    {
+        if let Event::PageFlip(_) = event {
+            *flip_pending = false;
+        }
+    }
+
+    Ok(())
+}
+
 /// The buffer implementation.
 pub(crate) struct BufferImpl<'a, D: ?Sized, W: ?Sized> {
     crtc: crtc::Handle,
     fb: framebuffer::Handle,
-    mapping: DumbMapping<'a>,
+    mapping: Mapping<'a>,
+
+    /// Cached atomic property handles, present only when the display supports atomic
+    /// modesetting.
+    atomic: Option<AtomicState>,
+
+    /// Borrowed from the surface; set once we issue a flip and cleared once its event
+    /// is drained.
+    flip_pending: &'a mut bool,
+
+    /// Surface size, used to clamp damage rectangles to the scanout bounds.
+    width: u32,
+    height: u32,
+
+    /// How many presents ago this buffer's contents were last on screen; 0 if it's
+    /// brand new or hasn't been presented yet. Snapshotted from `age_slot` when this
+    /// `BufferImpl` was created.
+    age: u8,
+
+    /// Borrowed from the surface; set to `Some(0)` once this buffer is actually
+    /// flipped, so a later `buffer_mut` call sees the correct age even if this one is
+    /// dropped without presenting.
+    age_slot: &'a mut Option<u8>,
+
+    /// Every other buffer's `age`, borrowed from the surface; aged by one once this
+    /// buffer is actually flipped, so staleness is only counted against real presents
+    /// rather than against every `buffer_mut` call.
+    other_ages: Vec<&'a mut Option<u8>>,
+
+    /// This buffer's slot index within `Buffers::buffers`.
+    index: usize,
+
+    /// Borrowed from the surface; updated to `Some(self.index)` once this buffer is
+    /// actually flipped, so `fetch` knows which buffer is on screen.
+    front_index: &'a mut Option<usize>,
 
     /// The display implementation.
     display: &'a KmsDisplayImpl<D>,
@@ -234,26 +718,25 @@ pub(crate) struct BufferImpl<'a, D: ?Sized, W: ?Sized> {
 impl<D: ?Sized, W: ?Sized> BufferInterface for BufferImpl<'_, D, W> {
     #[inline]
     fn pixels(&self) -> &[u32] {
-        bytemuck::cast_slice(self.mapping.as_ref())
+        bytemuck::cast_slice(self.mapping.as_bytes())
     }
 
     #[inline]
     fn pixels_mut(&mut self) -> &mut [u32] {
-        bytemuck::cast_slice_mut(self.mapping.as_mut())
+        bytemuck::cast_slice_mut(self.mapping.as_bytes_mut())
     }
 
     #[inline]
     fn age(&self) -> u8 {
-        2
+        self.age
     }
 
     #[inline]
-    fn present_with_damage(self, _damage: &[crate::Rect]) -> Result<(), SoftBufferError> {
-        self.display
-            .page_flip(self.crtc, self.fb, PageFlipFlags::EVENT, None)
-            .unwrap();
-
-        Ok(())
+    fn present_with_damage(mut self, damage: &[crate::Rect]) -> Result<(), SoftBufferError> {
+        // A flip that's still in flight would make the kernel reject the next one
+        // with `EBUSY`; wait for vblank instead of letting that bubble up as an error.
+        wait_for_flip(self.display, self.flip_pending)?;
+        self.flip(damage)
     }
 
     #[inline]
@@ -262,32 +745,231 @@ impl<D: ?Sized, W: ?Sized> BufferInterface for BufferImpl<'_, D, W> {
     }
 }
 
+/// KMS-specific, non-blocking presentation that isn't part of the cross-backend
+/// `Buffer` API.
+///
+/// Implemented for the KMS backend's buffer type; import this trait to reach the
+/// methods on any `Buffer` backed by it.
+pub trait KmsBufferExt {
+    /// Present without blocking. Returns `Ok(false)` instead of flipping if a
+    /// previously issued flip hasn't landed yet (the kernel would otherwise reject a
+    /// second one with `EBUSY`), so callers can build a proper frame loop that polls
+    /// the card fd for readiness instead of stalling on vblank.
+    fn try_present_with_damage(self, damage: &[crate::Rect]) -> Result<bool, SoftBufferError>;
+}
+
+impl<D: ?Sized, W: ?Sized> KmsBufferExt for BufferImpl<'_, D, W> {
+    fn try_present_with_damage(mut self, damage: &[crate::Rect]) -> Result<bool, SoftBufferError> {
+        poll_flip_events(self.display, self.flip_pending)?;
+        if *self.flip_pending {
+            return Ok(false);
+        }
+
+        self.flip(damage)?;
+        Ok(true)
+    }
+}
+
+impl<D: ?Sized, W: ?Sized> BufferImpl<'_, D, W> {
+    /// Issue the actual plane/CRTC flip (atomic or legacy) and mark it as pending.
+    ///
+    /// On the atomic path, non-empty `damage` is attached to the primary plane's
+    /// `FB_DAMAGE_CLIPS` property so the driver/compositor can restrict scanout
+    /// reuploads to the changed regions; an empty slice means full-surface damage and
+    /// the property is left unset.
+    fn flip(&mut self, damage: &[crate::Rect]) -> Result<(), SoftBufferError> {
+        // For GBM-backed buffers, CPU writes only land in a shadow copy (see
+        // `Mapping::Gbm`); push them into the real buffer object before it's handed
+        // to the kernel for scanout.
+        self.mapping.flush()?;
+
+        match &self.atomic {
+            Some(atomic) => {
+                let mut req = AtomicModeReq::new();
+                req.add_property(
+                    atomic.plane,
+                    atomic.plane_fb_id,
+                    property::Value::Framebuffer(Some(self.fb)),
+                );
+
+                if let Some(fb_damage_clips) = atomic.plane_fb_damage_clips {
+                    let rects: Vec<DrmModeRect> = damage
+                        .iter()
+                        .filter_map(|rect| clamp_damage_rect(rect, self.width, self.height))
+                        .collect();
+
+                    if !rects.is_empty() {
+                        // `Device::create_property_blob` is generic over a `Sized` `T`
+                        // and can't take the `&[u8]` a dynamically-sized rect list casts
+                        // to; go through `drm_ffi` directly instead, which is the same
+                        // thing `Device::create_property_blob` does under the hood.
+                        let mut bytes: Vec<u8> = bytemuck::cast_slice(&rects).to_vec();
+                        let blob_id = drm_ffi::mode::create_property_blob(
+                            self.display.as_fd(),
+                            &mut bytes,
+                        )
+                        .swbuf_err("Failed to create damage clip blob")?
+                        .blob_id;
+                        req.add_property(
+                            atomic.plane,
+                            fb_damage_clips,
+                            property::Value::Blob(blob_id.into()),
+                        );
+                    }
+                }
+
+                self.display
+                    .atomic_commit(AtomicCommitFlags::PAGE_FLIP_EVENT, req)
+                    .swbuf_err("Failed to commit atomic page flip")?;
+            }
+            None => {
+                self.display
+                    .page_flip(self.crtc, self.fb, PageFlipFlags::EVENT, None)
+                    .swbuf_err("Failed to page flip")?;
+            }
+        }
+
+        *self.front_index = Some(self.index);
+        *self.age_slot = Some(0);
+        // Every other buffer just aged by one, now that this one actually took its
+        // place on screen.
+        for age in self.other_ages.iter_mut() {
+            **age = age.map(|a| a.saturating_add(1));
+        }
+
+        *self.flip_pending = true;
+        Ok(())
+    }
+}
+
+/// A clip rectangle as the kernel expects it in an `FB_DAMAGE_CLIPS` blob: an inclusive
+/// top-left corner and an exclusive bottom-right one (`struct drm_mode_rect`).
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DrmModeRect {
+    x1: i32,
+    y1: i32,
+    x2: i32,
+    y2: i32,
+}
+
+// SAFETY: `DrmModeRect` is a `repr(C)` struct of four `i32` fields with no padding or
+// interior invalid bit patterns.
+unsafe impl bytemuck::Zeroable for DrmModeRect {}
+unsafe impl bytemuck::Pod for DrmModeRect {}
+
+/// Clamp a damage rectangle to the `width`x`height` surface bounds, returning `None`
+/// if it falls entirely outside of them.
+fn clamp_damage_rect(rect: &crate::Rect, width: u32, height: u32) -> Option<DrmModeRect> {
+    let x1 = rect.x.min(width);
+    let y1 = rect.y.min(height);
+    let x2 = rect.x.saturating_add(rect.width.get()).min(width);
+    let y2 = rect.y.saturating_add(rect.height.get()).min(height);
+
+    (x2 > x1 && y2 > y1).then(|| DrmModeRect {
+        x1: x1 as i32,
+        y1: y1 as i32,
+        x2: x2 as i32,
+        y2: y2 as i32,
+    })
+}
+
 #[derive(Debug)]
 struct Buffers {
-    /// The involved set of buffers.
-    buffers: [SharedBuffer; 2],
+    /// The involved set of buffers (2 for double-buffering, 3 for triple-, ...).
+    buffers: Vec<SharedBuffer>,
+
+    /// Index of the buffer to hand out on the next `buffer_mut` call.
+    next: usize,
 
-    /// Whether to use the first buffer or the second buffer as the front buffer.
-    first_is_front: bool,
+    /// Index of the buffer currently scanned out, i.e. the one last presented.
+    /// `None` until the first `present` call.
+    front: Option<usize>,
 }
 
-/// The combined frame buffer and dumb buffer.
+/// The combined frame buffer and backing storage (a dumb buffer, or optionally a GBM
+/// buffer object).
 #[derive(Debug)]
 struct SharedBuffer {
     /// The frame buffer.
     fb: framebuffer::Handle,
 
-    /// The dumb buffer.
-    db: DumbBuffer,
+    /// The CPU-mappable storage backing `fb`.
+    backing: Backing,
+
+    /// Presents of other buffers since this buffer was last presented (0 right after
+    /// its own present), or `None` if it never has been presented.
+    age: Option<u8>,
+}
+
+/// The allocation strategy used for a `SharedBuffer`'s storage.
+#[derive(Debug)]
+enum Backing {
+    /// Linear, CPU-mapped memory allocated through the dumb-buffer ioctls. Always
+    /// supported, but can be slow or unsupported for scanout on some GPUs.
+    Dumb(DumbBuffer),
+
+    /// Allocated through a `gbm::Device`, letting the driver pick its preferred
+    /// tiling/modifiers. Only available with the `kms-gbm` feature.
+    #[cfg(feature = "kms-gbm")]
+    Gbm(GbmBacking),
+}
+
+/// A GBM-backed buffer's storage.
+///
+/// `gbm::BufferObject::map_mut` only hands out a mapping for the duration of a
+/// callback, so it can't back a `Mapping` that needs to stay valid across separate
+/// `pixels`/`pixels_mut`/`present` calls the way a `DumbMapping` can. Instead, CPU
+/// reads and writes go through an owned shadow copy of the buffer's bytes, which is
+/// pushed into the buffer object with `write` right before it's scanned out.
+#[cfg(feature = "kms-gbm")]
+#[derive(Debug)]
+struct GbmBacking {
+    bo: gbm::BufferObject<()>,
+    width: u32,
+    height: u32,
+    stride: u32,
+    shadow: Vec<u8>,
 }
 
 impl SharedBuffer {
-    /// Create a new buffer set.
+    /// Create a new buffer set, preferring a GBM allocation when the display opened
+    /// one and falling back to a dumb buffer otherwise.
     pub(crate) fn new<D: ?Sized>(
         display: &KmsDisplayImpl<D>,
         width: NonZeroU32,
         height: NonZeroU32,
     ) -> Result<Self, SoftBufferError> {
+        #[cfg(feature = "kms-gbm")]
+        if let Some(gbm) = &display.gbm {
+            if let Ok(bo) = gbm.create_buffer_object::<()>(
+                width.get(),
+                height.get(),
+                gbm::Format::Xrgb8888,
+                gbm::BufferObjectFlags::SCANOUT | gbm::BufferObjectFlags::WRITE,
+            ) {
+                let fb = display
+                    .add_framebuffer(&bo, 24, 32)
+                    .swbuf_err("failed to add GBM-backed framebuffer")?;
+                let stride = bo
+                    .stride()
+                    .swbuf_err("failed to query GBM buffer object stride")?;
+                let shadow = vec![0u8; stride as usize * height.get() as usize];
+
+                return Ok(SharedBuffer {
+                    fb,
+                    backing: Backing::Gbm(GbmBacking {
+                        bo,
+                        width: width.get(),
+                        height: height.get(),
+                        stride,
+                        shadow,
+                    }),
+                    age: None,
+                });
+            }
+        }
+
         let db = display
             .create_dumb_buffer((width.get(), height.get()), DrmFourcc::Xrgb8888, 32)
             .swbuf_err("failed to create dumb buffer")?;
@@ -295,6 +977,109 @@ impl SharedBuffer {
             .add_framebuffer(&db, 24, 32)
             .swbuf_err("failed to add framebuffer")?;
 
-        Ok(SharedBuffer { fb, db })
+        Ok(SharedBuffer {
+            fb,
+            backing: Backing::Dumb(db),
+            age: None,
+        })
+    }
+
+    /// The buffer's pixel dimensions.
+    fn size(&self) -> (u32, u32) {
+        match &self.backing {
+            Backing::Dumb(db) => db.size(),
+            #[cfg(feature = "kms-gbm")]
+            Backing::Gbm(gbm) => (gbm.width, gbm.height),
+        }
+    }
+
+    /// The row pitch (stride), in bytes, of the mapped storage.
+    fn pitch(&self) -> u32 {
+        match &self.backing {
+            Backing::Dumb(db) => db.pitch(),
+            #[cfg(feature = "kms-gbm")]
+            Backing::Gbm(gbm) => gbm.stride,
+        }
+    }
+
+    /// Best-effort teardown of the kernel resources backing this buffer so that
+    /// repeated `resize` calls don't leak GEM handles and FB ids. GBM buffer objects
+    /// free themselves on `Drop`, so only the framebuffer needs an explicit destroy
+    /// in that case.
+    fn destroy<D: ?Sized>(self, display: &KmsDisplayImpl<D>) {
+        if let Err(e) = display.destroy_framebuffer(self.fb) {
+            warn!("Failed to destroy framebuffer: {e}");
+        }
+        if let Backing::Dumb(db) = self.backing {
+            if let Err(e) = display.destroy_dumb_buffer(db) {
+                warn!("Failed to destroy dumb buffer: {e}");
+            }
+        }
+    }
+}
+
+impl Backing {
+    /// Map the buffer for CPU access.
+    ///
+    /// Defined on `Backing` rather than `SharedBuffer` so that mapping only borrows
+    /// the backing storage, leaving the rest of the `SharedBuffer` (its `age`) free
+    /// to be borrowed independently.
+    fn map<'a, D: ?Sized>(
+        &'a mut self,
+        display: &'a KmsDisplayImpl<D>,
+    ) -> Result<Mapping<'a>, SoftBufferError> {
+        match self {
+            Backing::Dumb(db) => Ok(Mapping::Dumb(
+                display
+                    .map_dumb_buffer(db)
+                    .swbuf_err("Failed to map dumb buffer")?,
+            )),
+            #[cfg(feature = "kms-gbm")]
+            Backing::Gbm(gbm) => Ok(Mapping::Gbm {
+                shadow: &mut gbm.shadow,
+                bo: &mut gbm.bo,
+            }),
+        }
+    }
+}
+
+/// A CPU mapping of a `SharedBuffer`, regardless of which `Backing` it uses.
+enum Mapping<'a> {
+    Dumb(DumbMapping<'a>),
+    #[cfg(feature = "kms-gbm")]
+    Gbm {
+        shadow: &'a mut [u8],
+        bo: &'a mut gbm::BufferObject<()>,
+    },
+}
+
+impl Mapping<'_> {
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Mapping::Dumb(mapping) => mapping.as_ref(),
+            #[cfg(feature = "kms-gbm")]
+            Mapping::Gbm { shadow, .. } => shadow,
+        }
+    }
+
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        match self {
+            Mapping::Dumb(mapping) => mapping.as_mut(),
+            #[cfg(feature = "kms-gbm")]
+            Mapping::Gbm { shadow, .. } => shadow,
+        }
+    }
+
+    /// Push CPU-side writes into the real buffer object before it's scanned out.
+    /// A no-op for dumb buffers, whose mapping already aliases the real storage.
+    fn flush(&mut self) -> Result<(), SoftBufferError> {
+        match self {
+            Mapping::Dumb(_) => Ok(()),
+            #[cfg(feature = "kms-gbm")]
+            Mapping::Gbm { shadow, bo } => bo
+                .write(shadow)
+                .swbuf_err("Failed to write GBM buffer object")?
+                .swbuf_err("Failed to write GBM buffer object"),
+        }
     }
 }